@@ -1,7 +1,11 @@
 use smallvec::SmallVec;
 
+use std::collections::HashMap;
+
 use std::fmt::Debug;
 
+use std::ops::Range;
+
 pub enum AstNodeKind {
     Text,
 
@@ -26,6 +30,16 @@ pub enum AstNode<'a> {
     Text(TextAstNode),
 }
 
+/// A node paired with its absolute start offset in the document.
+///
+/// Produced by [`AstNode::node_at_offset`]; the `offset` is the node's start,
+/// so `offset..offset + node.length()` is the byte range it covers.
+pub struct NodeRef<'a> {
+    pub node: &'a AstNode<'a>,
+
+    pub offset: u64,
+}
+
 impl<'a> AstNode<'a> {
     pub fn kind(&self) -> AstNodeKind {
         match self {
@@ -148,12 +162,330 @@ impl<'a> AstNode<'a> {
             _ => usize::MAX,
         }
     }
+
+    /// Return the ancestor chain from the root down to the innermost node that
+    /// contains byte offset `target`, each paired with its start offset.
+    ///
+    /// Start offsets are accumulated while descending: a `Pair`'s child starts
+    /// after its opening bracket, and within a `List` each child starts after
+    /// the sum of its preceding siblings' lengths. This is the bracket-tree
+    /// analogue of rust-analyzer's `ancestors_at_offset`. The chain is empty
+    /// when `target` lies outside the root.
+    pub fn node_at_offset(&'a self, target: u64) -> Vec<NodeRef<'a>> {
+        let mut chain = Vec::new();
+
+        self.collect_at_offset(0, target, &mut chain);
+
+        chain
+    }
+
+    fn collect_at_offset(&'a self, start: u64, target: u64, chain: &mut Vec<NodeRef<'a>>) {
+        if target < start || target >= start + self.length() {
+            return;
+        }
+
+        chain.push(NodeRef { node: self, offset: start });
+
+        match self {
+            AstNode::Pair(pair) => {
+                if let Some(child) = pair.child {
+                    child.collect_at_offset(start + pair.opening_bracket.length, target, chain);
+                }
+            }
+
+            AstNode::List(list) => {
+                let mut child_start = start;
+
+                for child in &list.children {
+                    if target < child_start + child.length() {
+                        child.collect_at_offset(child_start, target, chain);
+
+                        break;
+                    }
+
+                    child_start += child.length();
+                }
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Return the innermost [`PairAstNode`] enclosing `offset`, if any.
+    pub fn enclosing_pair(&'a self, offset: u64) -> Option<NodeRef<'a>> {
+        self.node_at_offset(offset)
+            .into_iter()
+            .rev()
+            .find(|node_ref| matches!(node_ref.node, AstNode::Pair(_)))
+    }
+
+    /// Given a position on an opening or closing bracket, return the start
+    /// offset of its partner by walking the enclosing [`PairAstNode`].
+    ///
+    /// Returns `None` when `offset` is not on a bracket, or when the pair has
+    /// no closing bracket to match.
+    pub fn matching_bracket(&'a self, offset: u64) -> Option<u64> {
+        for node_ref in self.node_at_offset(offset).into_iter().rev() {
+            let AstNode::Pair(pair) = node_ref.node else {
+                continue;
+            };
+
+            let open_start = node_ref.offset;
+
+            let open_end = open_start + pair.opening_bracket.length;
+
+            let close_start = open_end + pair.child.map_or(0, |c| c.length());
+
+            if offset >= open_start && offset < open_end {
+                return pair.closing_bracket.as_ref().map(|_| close_start);
+            }
+
+            if let Some(closing) = &pair.closing_bracket {
+                if offset >= close_start && offset < close_start + closing.length {
+                    return Some(open_start);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Assign a colorization slot to every bracket in the tree in a single
+    /// traversal, for bracket-pair colorization.
+    ///
+    /// Depth is a pure function of [`PairAstNode`] nesting: entering a pair
+    /// increments it, so both that pair's opening and closing brackets are
+    /// colored `depth % palette_size`. An [`InvalidBracketAstNode`] is given the
+    /// distinguished [`BracketColorIndex::Error`] slot instead.
+    pub fn assign_bracket_colors(&self, palette_size: usize) -> Vec<BracketColor> {
+        let mut colors = Vec::new();
+
+        self.collect_colors(0, 0, palette_size, &mut colors);
+
+        colors
+    }
+
+    fn collect_colors(
+        &self,
+        start: u64,
+        depth: usize,
+        palette_size: usize,
+        out: &mut Vec<BracketColor>,
+    ) {
+        match self {
+            AstNode::Pair(pair) => {
+                let depth = depth + 1;
+
+                let color = BracketColorIndex::at_depth(depth, palette_size);
+
+                out.push(BracketColor {
+                    position: start,
+
+                    length: pair.opening_bracket.length,
+
+                    color_index: color,
+                });
+
+                let child_start = start + pair.opening_bracket.length;
+
+                if let Some(child) = pair.child {
+                    child.collect_colors(child_start, depth, palette_size, out);
+                }
+
+                if let Some(closing) = &pair.closing_bracket {
+                    let close_start = child_start + pair.child.map_or(0, |c| c.length());
+
+                    out.push(BracketColor {
+                        position: close_start,
+
+                        length: closing.length,
+
+                        color_index: color,
+                    });
+                }
+            }
+
+            AstNode::List(list) => {
+                let mut child_start = start;
+
+                for child in &list.children {
+                    child.collect_colors(child_start, depth, palette_size, out);
+
+                    child_start += child.length();
+                }
+            }
+
+            AstNode::InvalidBracket(node) => {
+                out.push(BracketColor {
+                    position: start,
+
+                    length: node.length,
+
+                    color_index: BracketColorIndex::Error,
+                });
+            }
+
+            _ => {}
+        }
+    }
+
+    /// Like [`assign_bracket_colors`](Self::assign_bracket_colors), but each
+    /// bracket kind keeps its own depth counter so that `()` and `{}` nesting
+    /// are colored independently of one another.
+    pub fn assign_bracket_colors_per_kind(&self, palette_size: usize) -> Vec<BracketColor> {
+        let mut depths: HashMap<char, usize> = HashMap::new();
+
+        let mut out = Vec::new();
+
+        self.collect_colors_per_kind(0, &mut depths, palette_size, &mut out);
+
+        out
+    }
+
+    fn collect_colors_per_kind(
+        &self,
+        start: u64,
+        depths: &mut HashMap<char, usize>,
+        palette_size: usize,
+        out: &mut Vec<BracketColor>,
+    ) {
+        match self {
+            AstNode::Pair(pair) => {
+                let kind = pair.opening_bracket.bracket_type;
+
+                let depth = depths.entry(kind).or_insert(0);
+
+                *depth += 1;
+
+                let color = BracketColorIndex::at_depth(*depth, palette_size);
+
+                out.push(BracketColor {
+                    position: start,
+
+                    length: pair.opening_bracket.length,
+
+                    color_index: color,
+                });
+
+                let child_start = start + pair.opening_bracket.length;
+
+                if let Some(child) = pair.child {
+                    child.collect_colors_per_kind(child_start, depths, palette_size, out);
+                }
+
+                if let Some(closing) = &pair.closing_bracket {
+                    let close_start = child_start + pair.child.map_or(0, |c| c.length());
+
+                    out.push(BracketColor {
+                        position: close_start,
+
+                        length: closing.length,
+
+                        color_index: color,
+                    });
+                }
+
+                *depths.entry(kind).or_insert(1) -= 1;
+            }
+
+            AstNode::List(list) => {
+                let mut child_start = start;
+
+                for child in &list.children {
+                    child.collect_colors_per_kind(child_start, depths, palette_size, out);
+
+                    child_start += child.length();
+                }
+            }
+
+            AstNode::InvalidBracket(node) => {
+                out.push(BracketColor {
+                    position: start,
+
+                    length: node.length,
+
+                    color_index: BracketColorIndex::Error,
+                });
+            }
+
+            _ => {}
+        }
+    }
+}
+
+/// Palette slot assigned to a bracket by [`AstNode::assign_bracket_colors`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BracketColorIndex {
+    /// Nesting depth wrapped into the palette: `depth % palette_size`.
+    Depth(usize),
+
+    /// Reserved slot for an unmatched / invalid bracket.
+    Error,
+}
+
+impl BracketColorIndex {
+    fn at_depth(depth: usize, palette_size: usize) -> Self {
+        if palette_size == 0 {
+            BracketColorIndex::Depth(0)
+        } else {
+            BracketColorIndex::Depth(depth % palette_size)
+        }
+    }
+}
+
+/// A single colored bracket span, ready to hand to the editor's syntax layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BracketColor {
+    pub position: u64,
+
+    pub length: u64,
+
+    pub color_index: BracketColorIndex,
 }
 
+/// A read-only view of document text for the AST to scan.
+///
+/// The interface is chunk-iterator based rather than slice based so a backing
+/// store need not be a single contiguous buffer: a rope or piece-table can hand
+/// back its internal pieces directly, and scanners stream across chunk
+/// boundaries instead of materializing the whole region. [`StrTextModel`] is the
+/// thin contiguous-buffer adapter for callers still backed by a plain `&str`.
 pub trait TextModel {
-    fn get_text_range(&self, range: std::ops::Range<usize>) -> &str;
+    /// Yield the text covering `range` as a sequence of borrowed chunks, in
+    /// document order. A contiguous store yields a single chunk; a rope yields
+    /// one per internal piece.
+    fn text_chunks(&self, range: Range<u64>) -> impl Iterator<Item = &str>;
+
+    /// Number of leading-whitespace characters on `line` (0-based), used by
+    /// indentation-sensitive features that want a single line's indent without
+    /// scanning a range.
+    fn line_indent(&self, line: usize) -> usize;
+}
+
+/// Thin contiguous-buffer adapter implementing [`TextModel`] over a single
+/// `&str`, for callers not yet backed by a rope/piece-table.
+pub struct StrTextModel<'a> {
+    text: &'a str,
+}
+
+impl<'a> StrTextModel<'a> {
+    pub fn new(text: &'a str) -> Self {
+        StrTextModel { text }
+    }
+}
+
+impl TextModel for StrTextModel<'_> {
+    fn text_chunks(&self, range: Range<u64>) -> impl Iterator<Item = &str> {
+        std::iter::once(&self.text[range.start as usize..range.end as usize])
+    }
 
-    fn get_line(&self, line_number: usize) -> &str;
+    fn line_indent(&self, line: usize) -> usize {
+        self.text
+            .lines()
+            .nth(line)
+            .map(|l| l.chars().take_while(|c| c.is_whitespace()).count())
+            .unwrap_or(0)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -305,21 +637,194 @@ impl<'a> ListAstNode<'a> {
     }
 
     pub fn compute_min_indentation(&self, offset: u64, text_model: &impl TextModel) -> usize {
-        let text = text_model.get_text_range(offset as usize..(offset + self.length) as usize);
+        let mut min = usize::MAX;
+
+        // Column of the first non-whitespace char on the current line, if any,
+        // and the running column counter — both carried across chunk boundaries
+        // so no full slice of the region is ever allocated.
+        let mut first_non_ws: Option<usize> = None;
+
+        let mut column = 0usize;
+
+        for chunk in text_model.text_chunks(offset..offset + self.length) {
+            for c in chunk.chars() {
+                if c == '\n' {
+                    min = min.min(first_non_ws.unwrap_or(usize::MAX));
+
+                    first_non_ws = None;
+
+                    column = 0;
+                } else {
+                    if first_non_ws.is_none() && !c.is_whitespace() {
+                        first_non_ws = Some(column);
+                    }
 
-        text.lines()
-            .map(|line| {
-                line.chars()
-                    .position(|c| !c.is_whitespace())
-                    .unwrap_or(usize::MAX)
-            })
-            .min()
-            .unwrap_or(usize::MAX)
+                    column += 1;
+                }
+            }
+        }
+
+        min.min(first_non_ws.unwrap_or(usize::MAX))
     }
 
     pub fn list_height(&self) -> usize {
         self.list_height
     }
+
+    /// Build a balanced 2-3 tree over `children` by folding [`concat`].
+    ///
+    /// The result keeps every interior [`ListAstNode`] holding 2 or 3 children
+    /// of equal `list_height`, so the tree stays ~log(n) deep no matter how the
+    /// children were grouped on the way in. Used by reparsing to rebalance a
+    /// flattened child list so subtree reuse stays cheap.
+    pub fn from_children_balanced(children: Vec<Box<AstNode<'a>>>) -> AstNode<'a> {
+        let mut nodes = children.into_iter();
+
+        let first = match nodes.next() {
+            Some(node) => *node,
+
+            None => {
+                return AstNode::List(ListAstNode {
+                    length: 0,
+
+                    list_height: 0,
+
+                    missing_opening_bracket_ids: SmallVec::new(),
+
+                    children: Vec::new(),
+                });
+            }
+        };
+
+        nodes.fold(first, |acc, node| concat(acc, *node))
+    }
+}
+
+/// Assemble a [`ListAstNode`] from children that are already all of equal
+/// height, recomputing `length` and `missing_opening_bracket_ids` from them.
+///
+/// The resulting node sits one level above its children.
+fn balanced_list<'a>(children: Vec<Box<AstNode<'a>>>) -> ListAstNode<'a> {
+    let list_height = children.first().map_or(0, |c| c.list_height()) + 1;
+
+    let length = children.iter().map(|c| c.length()).sum();
+
+    let mut missing_opening_bracket_ids: SmallVec<[usize; 4]> = SmallVec::new();
+
+    for child in &children {
+        for id in child.missing_opening_bracket_ids() {
+            missing_opening_bracket_ids.push(id);
+        }
+    }
+
+    ListAstNode {
+        length,
+
+        list_height,
+
+        missing_opening_bracket_ids,
+
+        children,
+    }
+}
+
+/// Wrap a run of equal-height children into a node, splitting into two when a
+/// concat has pushed the count to 4 so the 2-or-3-children invariant holds.
+fn rebalanced_node<'a>(mut children: Vec<Box<AstNode<'a>>>) -> AstNode<'a> {
+    if children.len() <= 3 {
+        AstNode::List(balanced_list(children))
+    } else {
+        let right = children.split_off(2);
+
+        let left = Box::new(AstNode::List(balanced_list(children)));
+
+        let right = Box::new(AstNode::List(balanced_list(right)));
+
+        AstNode::List(balanced_list(vec![left, right]))
+    }
+}
+
+/// Concatenate two balanced 2-3 trees, preserving the balance invariant.
+///
+/// Equal heights join under a fresh parent one level up; otherwise the shorter
+/// tree descends the inner spine of the taller one, concatenating recursively
+/// and propagating any overflow back up via [`rebalanced_node`]. This mirrors
+/// the classic 2-3-tree `concat` and is what keeps `list_height` logarithmic.
+pub fn concat<'a>(a: AstNode<'a>, b: AstNode<'a>) -> AstNode<'a> {
+    let height_a = a.list_height();
+
+    let height_b = b.list_height();
+
+    if height_a == height_b {
+        AstNode::List(balanced_list(vec![Box::new(a), Box::new(b)]))
+    } else if height_a > height_b {
+        append_right(a, b)
+    } else {
+        prepend_left(a, b)
+    }
+}
+
+/// `concat` where `a` is the taller tree: descend into its rightmost child.
+fn append_right<'a>(a: AstNode<'a>, b: AstNode<'a>) -> AstNode<'a> {
+    let list = match a {
+        AstNode::List(list) => list,
+
+        _ => unreachable!("append_right requires a List on the left"),
+    };
+
+    let child_height = list.list_height - 1;
+
+    let mut children = list.children;
+
+    let last = *children.pop().expect("a List always has children");
+
+    let merged = concat(last, b);
+
+    if merged.list_height() == child_height {
+        children.push(Box::new(merged));
+    } else {
+        // A taller result is always a 2-child list; splice its children back in.
+        match merged {
+            AstNode::List(list) => children.extend(list.children),
+
+            other => children.push(Box::new(other)),
+        }
+    }
+
+    rebalanced_node(children)
+}
+
+/// `concat` where `b` is the taller tree: descend into its leftmost child.
+fn prepend_left<'a>(a: AstNode<'a>, b: AstNode<'a>) -> AstNode<'a> {
+    let list = match b {
+        AstNode::List(list) => list,
+
+        _ => unreachable!("prepend_left requires a List on the right"),
+    };
+
+    let child_height = list.list_height - 1;
+
+    let mut children = list.children;
+
+    let first = *children.remove(0);
+
+    let merged = concat(a, first);
+
+    if merged.list_height() == child_height {
+        children.insert(0, Box::new(merged));
+    } else {
+        match merged {
+            AstNode::List(list) => {
+                for (i, child) in list.children.into_iter().enumerate() {
+                    children.insert(i, child);
+                }
+            }
+
+            other => children.insert(0, Box::new(other)),
+        }
+    }
+
+    rebalanced_node(children)
 }
 
 #[derive(Debug, Clone)]
@@ -353,4 +858,775 @@ pub struct InvalidBracketAstNode {
 pub struct TextAstNode {
     pub length: u64,
     pub text: String,
-}
\ No newline at end of file
+}
+
+/// Whether a bracket token opens or closes a pair.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BracketKind {
+    Opening,
+
+    Closing,
+}
+
+/// A single lexed token handed to the incremental parser.
+///
+/// `Text` runs carry no bracket semantics; only their `length` matters so the
+/// parser can keep its cursor aligned with the document and the old tree.
+///
+/// Shared-id contract: the `id` of a `Close` must equal the `id` of the `Open`
+/// it matches — it names the bracket *pair*, not the individual token. Reuse
+/// correctness depends on it: an unmatched closer's id is recorded in a
+/// subtree's `missing_opening_bracket_ids`, and [`AstNode::can_be_reused`] later
+/// checks those against the ids of the openers still on the parse stack. A
+/// stream that numbered closers independently of their openers would make every
+/// such check spuriously pass or fail, silently. `bracket_type` still drives
+/// open/close *matching* (via [`closing_char`]); `id` only carries reuse
+/// identity across the two halves of a pair.
+#[derive(Debug, Clone)]
+pub enum BracketToken {
+    Open {
+        id: usize,
+
+        bracket_type: char,
+
+        length: u64,
+    },
+
+    Close {
+        id: usize,
+
+        bracket_type: char,
+
+        length: u64,
+    },
+
+    Text {
+        length: u64,
+    },
+}
+
+/// The closing bracket character that matches an opening one, if `open` is a
+/// known opening bracket. Used to decide whether a `Close` token belongs to a
+/// given open pair rather than consuming any closer indiscriminately.
+fn closing_char(open: char) -> Option<char> {
+    match open {
+        '(' => Some(')'),
+
+        '[' => Some(']'),
+
+        '{' => Some('}'),
+
+        '<' => Some('>'),
+
+        _ => None,
+    }
+}
+
+impl BracketToken {
+    pub fn length(&self) -> u64 {
+        match self {
+            BracketToken::Open { length, .. } => *length,
+
+            BracketToken::Close { length, .. } => *length,
+
+            BracketToken::Text { length } => *length,
+        }
+    }
+
+    pub fn kind(&self) -> Option<BracketKind> {
+        match self {
+            BracketToken::Open { .. } => Some(BracketKind::Opening),
+
+            BracketToken::Close { .. } => Some(BracketKind::Closing),
+
+            BracketToken::Text { .. } => None,
+        }
+    }
+}
+
+/// Left-to-right source of bracket tokens, positioned by byte offset.
+///
+/// The parser advances through the stream while keeping `offset` in lockstep
+/// with the document; when a subtree is reused wholesale it `skip`s the stream
+/// past the reused region instead of re-lexing its interior.
+///
+/// Implementations must honour the shared-id contract on [`BracketToken`]: a
+/// closer and the opener it matches carry the same `id`, or subtree reuse will
+/// misbehave.
+pub trait BracketTokenStream<'a> {
+    /// Byte offset of the next token (the parser cursor).
+    fn offset(&self) -> u64;
+
+    /// Look at the next token without consuming it.
+    fn peek(&self) -> Option<BracketToken>;
+
+    /// Consume and return the next token.
+    fn advance(&mut self) -> Option<BracketToken>;
+
+    /// Advance the cursor by `length` bytes without producing tokens, used when
+    /// an old subtree of that length has been spliced in unchanged.
+    fn skip(&mut self, length: u64);
+}
+
+/// Cursor over the previous parse tree, used to locate a subtree that starts at
+/// a given offset and can be spliced into the new tree unchanged.
+///
+/// This is the analogue of VS Code's `NodeReader`: it walks the old tree in
+/// document order, descending only as far as needed to answer a reuse query.
+struct NodeReader<'a> {
+    root: &'a AstNode<'a>,
+}
+
+impl<'a> NodeReader<'a> {
+    fn new(root: &'a AstNode<'a>) -> Self {
+        NodeReader { root }
+    }
+
+    /// Return the old subtree that begins exactly at `offset`, ends at or
+    /// before `edit_start`, and remains valid under `open_bracket_ids`.
+    ///
+    /// Descends from the root accumulating start offsets; a node is only
+    /// reusable once it lies entirely before the edit, so any node overlapping
+    /// or after the edit is skipped in favour of descending into it.
+    fn reusable_node_at(
+        &self,
+        offset: u64,
+        edit_start: u64,
+        open_bracket_ids: &SmallVec<[usize; 4]>,
+    ) -> Option<&'a AstNode<'a>> {
+        Self::find(self.root, 0, offset, edit_start, open_bracket_ids)
+    }
+
+    fn find(
+        node: &'a AstNode<'a>,
+        node_start: u64,
+        target: u64,
+        edit_start: u64,
+        open_bracket_ids: &SmallVec<[usize; 4]>,
+    ) -> Option<&'a AstNode<'a>> {
+        if node_start == target
+            && node_start + node.length() <= edit_start
+            && node.can_be_reused(open_bracket_ids.clone())
+        {
+            return Some(node);
+        }
+
+        // The target lies inside this node (or the node overlaps the edit):
+        // descend into the child that contains it, tracking child start offsets.
+        let mut child_start = node_start;
+
+        if let AstNode::Pair(pair) = node {
+            child_start += pair.opening_bracket.length;
+
+            if let Some(child) = pair.child {
+                if let Some(found) =
+                    Self::find(child, child_start, target, edit_start, open_bracket_ids)
+                {
+                    return Some(found);
+                }
+            }
+        }
+
+        if let AstNode::List(list) = node {
+            for child in &list.children {
+                if target < child_start || target >= child_start + child.length() {
+                    child_start += child.length();
+
+                    continue;
+                }
+
+                return Self::find(child, child_start, target, edit_start, open_bracket_ids);
+            }
+        }
+
+        None
+    }
+}
+
+/// Owns the fresh [`AstNode`]s a reparse allocates for the regions it rebuilds.
+///
+/// A [`PairAstNode`] stores its child by reference (`&'a AstNode<'a>`), so the
+/// rebuilt child nodes need a backing store that outlives the returned tree.
+/// The arena is that store: it hands out `&'a` references tied to its own
+/// lifetime, and frees everything at once when dropped — so reparsing on every
+/// keystroke no longer leaks a `Box` per pair.
+#[derive(Default)]
+pub struct AstArena<'a> {
+    // The `Box` is load-bearing, not a clippy::vec_box oversight: it gives each
+    // node a heap address that stays put as the `Vec` reallocates, which is
+    // exactly what the `unsafe` in `alloc` relies on to hand out a `&'a`.
+    #[allow(clippy::vec_box)]
+    nodes: std::cell::RefCell<Vec<Box<AstNode<'a>>>>,
+}
+
+impl<'a> AstArena<'a> {
+    pub fn new() -> Self {
+        AstArena {
+            nodes: std::cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Move `node` into the arena and return a reference valid for the arena's
+    /// lifetime.
+    pub fn alloc(&'a self, node: AstNode<'a>) -> &'a AstNode<'a> {
+        let boxed = Box::new(node);
+
+        let ptr: *const AstNode<'a> = &*boxed;
+
+        self.nodes.borrow_mut().push(boxed);
+
+        // SAFETY: the node lives inside a `Box`, so its address is stable even
+        // as the backing `Vec` grows; we never hand out `&mut` to it and never
+        // drop individual nodes, so this shared reference stays valid for as
+        // long as `&'a self`.
+        unsafe { &*ptr }
+    }
+}
+
+/// Incremental bracket-pair parser.
+///
+/// [`Parser::reparse`] rebuilds only the region of the tree overlapping or
+/// after an edit, splicing untouched subtrees from the previous parse straight
+/// back in. This is the VS Code bracket-pair reuse algorithm, recast onto the
+/// existing [`AstNode`] fields: the reuse predicate is exactly
+/// [`AstNode::can_be_reused`], gated on a node lying wholly before the edit.
+pub struct Parser;
+
+impl Parser {
+    /// Reparse `tokens` against `old_root`, reusing subtrees that precede
+    /// `edit` and are still valid in their surrounding bracket context. Fresh
+    /// nodes for the rebuilt regions are allocated in `arena`.
+    pub fn reparse<'a>(
+        old_root: &'a AstNode<'a>,
+        edit: Range<u64>,
+        tokens: &mut impl BracketTokenStream<'a>,
+        arena: &'a AstArena<'a>,
+    ) -> AstNode<'a> {
+        let reader = NodeReader::new(old_root);
+
+        let mut open = OpenStack::default();
+
+        Self::parse_list(&reader, tokens, &mut open, &edit, arena)
+    }
+
+    /// Parse a run of sibling nodes until a closing bracket for an enclosing
+    /// pair or the end of the stream, reusing old subtrees where possible.
+    ///
+    /// A closing bracket whose type matches some currently-open pair belongs to
+    /// an ancestor and ends the run; one that matches nothing is an unexpected
+    /// closer and is emitted as an [`InvalidBracketAstNode`], its id recorded as
+    /// an unmatched closing so `can_be_reused` can gate on it upstream.
+    fn parse_list<'a>(
+        reader: &NodeReader<'a>,
+        tokens: &mut impl BracketTokenStream<'a>,
+        open: &mut OpenStack,
+        edit: &Range<u64>,
+        arena: &'a AstArena<'a>,
+    ) -> AstNode<'a> {
+        let mut children: Vec<Box<AstNode<'a>>> = Vec::new();
+
+        let mut unopened: SmallVec<[usize; 4]> = SmallVec::new();
+
+        loop {
+            let cursor = tokens.offset();
+
+            // Fast path: if an old subtree starts here, ends before the edit and
+            // is still valid, splice it in and skip re-tokenizing its interior.
+            if let Some(node) = reader.reusable_node_at(cursor, edit.start, &open.ids) {
+                if node.length() > 0 {
+                    tokens.skip(node.length());
+
+                    children.push(Box::new(node.deep_clone()));
+
+                    continue;
+                }
+            }
+
+            match tokens.peek() {
+                None => break,
+
+                Some(BracketToken::Close {
+                    id,
+                    bracket_type,
+                    length,
+                }) => {
+                    // A closer for an ancestor pair ends this run so that
+                    // ancestor can consume it; anything else has no opener in
+                    // scope and becomes an unexpected-closing bracket.
+                    if open.is_expected_closer(bracket_type) {
+                        break;
+                    }
+
+                    tokens.advance();
+
+                    children.push(Box::new(AstNode::InvalidBracket(InvalidBracketAstNode {
+                        length,
+
+                        bracket_type,
+
+                        position: (tokens.offset() - length) as usize,
+
+                        expected_bracket_type: None,
+
+                        metadata: None,
+                    })));
+
+                    unopened.push(id);
+                }
+
+                Some(BracketToken::Open { id, .. }) => {
+                    children.push(Box::new(Self::parse_pair(
+                        reader, tokens, open, edit, arena, id,
+                    )));
+                }
+
+                Some(BracketToken::Text { length }) => {
+                    tokens.advance();
+
+                    children.push(Box::new(AstNode::Text(TextAstNode {
+                        length,
+
+                        text: String::new(),
+                    })));
+                }
+            }
+        }
+
+        let node = match children.len() {
+            1 if unopened.is_empty() => *children.pop().unwrap(),
+
+            // Fold the siblings into a balanced 2-3 tree so `list_height` stays
+            // logarithmic and spliced subtrees keep their reuse cost low.
+            _ => ListAstNode::from_children_balanced(children),
+        };
+
+        // Record the unmatched closers seen directly at this level so a reused
+        // copy of this run is rejected once the matching opener disappears.
+        attach_unopened(node, unopened)
+    }
+
+    /// Parse a bracket pair whose opening token has already been peeked.
+    fn parse_pair<'a>(
+        reader: &NodeReader<'a>,
+        tokens: &mut impl BracketTokenStream<'a>,
+        open: &mut OpenStack,
+        edit: &Range<u64>,
+        arena: &'a AstArena<'a>,
+        id: usize,
+    ) -> AstNode<'a> {
+        let opening = match tokens.advance() {
+            Some(BracketToken::Open {
+                bracket_type,
+                length,
+                ..
+            }) => BracketAstNode {
+                length,
+
+                bracket_type,
+
+                position: (tokens.offset() - length) as usize,
+
+                metadata: None,
+            },
+
+            _ => unreachable!("parse_pair called without an opening bracket"),
+        };
+
+        open.push(id, opening.bracket_type);
+
+        let child = Self::parse_list(reader, tokens, open, edit, arena);
+
+        open.pop();
+
+        // A pair inherits the unmatched-closing ids of its child. Only a closer
+        // whose type matches this opening is consumed as the pair's closer; an
+        // unclosed pair records nothing extra (a missing *closer* is not a
+        // missing *opener*, which is all `can_be_reused` gates on).
+        let missing_opening_bracket_ids = child.missing_opening_bracket_ids();
+
+        let closing_bracket = match tokens.peek() {
+            Some(BracketToken::Close {
+                bracket_type,
+                length,
+                ..
+            }) if closing_char(opening.bracket_type) == Some(bracket_type) => {
+                tokens.advance();
+
+                Some(BracketAstNode {
+                    length,
+
+                    bracket_type,
+
+                    position: (tokens.offset() - length) as usize,
+
+                    metadata: None,
+                })
+            }
+
+            _ => None,
+        };
+
+        let child_length = child.length();
+
+        let closing_length = closing_bracket.as_ref().map_or(0, |b| b.length);
+
+        AstNode::Pair(PairAstNode {
+            length: opening.length + child_length + closing_length,
+
+            opening_bracket: opening,
+
+            child: Some(arena.alloc(child)),
+
+            closing_bracket,
+
+            missing_opening_bracket_ids,
+        })
+    }
+}
+
+/// Stack of the currently-open brackets, keyed by id for reuse checks and by
+/// type so a closing token can be matched against its opener.
+#[derive(Default)]
+struct OpenStack {
+    ids: SmallVec<[usize; 4]>,
+
+    types: SmallVec<[char; 4]>,
+}
+
+impl OpenStack {
+    fn push(&mut self, id: usize, bracket_type: char) {
+        self.ids.push(id);
+
+        self.types.push(bracket_type);
+    }
+
+    fn pop(&mut self) {
+        self.ids.pop();
+
+        self.types.pop();
+    }
+
+    /// Whether `close_type` closes any bracket currently open in scope.
+    fn is_expected_closer(&self, close_type: char) -> bool {
+        self.types
+            .iter()
+            .any(|&open| closing_char(open) == Some(close_type))
+    }
+}
+
+/// Fold a set of unmatched-closing ids into `node`'s `missing_opening_bracket_ids`.
+///
+/// Lists and pairs carry the set directly; a bare leaf (e.g. a lone unexpected
+/// closer) is wrapped in a one-child list so the ids still reach every ancestor.
+fn attach_unopened(node: AstNode<'_>, ids: SmallVec<[usize; 4]>) -> AstNode<'_> {
+    if ids.is_empty() {
+        return node;
+    }
+
+    let merge = |target: &mut SmallVec<[usize; 4]>| {
+        for id in ids {
+            if !target.contains(&id) {
+                target.push(id);
+            }
+        }
+    };
+
+    match node {
+        AstNode::List(mut list) => {
+            merge(&mut list.missing_opening_bracket_ids);
+
+            AstNode::List(list)
+        }
+
+        AstNode::Pair(mut pair) => {
+            merge(&mut pair.missing_opening_bracket_ids);
+
+            AstNode::Pair(pair)
+        }
+
+        other => {
+            let mut list = balanced_list(vec![Box::new(other)]);
+
+            merge(&mut list.missing_opening_bracket_ids);
+
+            AstNode::List(list)
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// In-memory [`BracketTokenStream`] over a flat token list, tracking the
+    /// byte cursor so `skip` can jump past a spliced-in subtree.
+    struct VecStream {
+        tokens: Vec<BracketToken>,
+        index: usize,
+        offset: u64,
+    }
+
+    impl VecStream {
+        fn new(tokens: Vec<BracketToken>) -> Self {
+            VecStream {
+                tokens,
+                index: 0,
+                offset: 0,
+            }
+        }
+    }
+
+    impl<'a> BracketTokenStream<'a> for VecStream {
+        fn offset(&self) -> u64 {
+            self.offset
+        }
+
+        fn peek(&self) -> Option<BracketToken> {
+            self.tokens.get(self.index).cloned()
+        }
+
+        fn advance(&mut self) -> Option<BracketToken> {
+            let token = self.tokens.get(self.index).cloned();
+
+            if let Some(token) = &token {
+                self.offset += token.length();
+
+                self.index += 1;
+            }
+
+            token
+        }
+
+        fn skip(&mut self, length: u64) {
+            let target = self.offset + length;
+
+            while self.offset < target {
+                match self.tokens.get(self.index) {
+                    Some(token) => {
+                        self.offset += token.length();
+
+                        self.index += 1;
+                    }
+
+                    None => break,
+                }
+            }
+        }
+    }
+
+    fn bracket(bracket_type: char, position: usize, metadata: Option<&str>) -> BracketAstNode {
+        BracketAstNode {
+            length: 1,
+            bracket_type,
+            position,
+            metadata: metadata.map(str::to_owned),
+        }
+    }
+
+    /// An old tree `()abc`: a `()` pair at offset 0 tagged with `marker` on its
+    /// opening bracket, followed by a three-byte text run.
+    fn old_tree<'a>(marker: &str, missing: SmallVec<[usize; 4]>) -> AstNode<'a> {
+        let pair = AstNode::Pair(PairAstNode {
+            length: 2,
+            opening_bracket: bracket('(', 0, Some(marker)),
+            child: None,
+            closing_bracket: Some(bracket(')', 1, None)),
+            missing_opening_bracket_ids: missing,
+        });
+
+        let text = AstNode::Text(TextAstNode {
+            length: 3,
+            text: "abc".to_owned(),
+        });
+
+        ListAstNode::from_children_balanced(vec![Box::new(pair), Box::new(text)])
+    }
+
+    fn tokens_for_oab() -> Vec<BracketToken> {
+        vec![
+            BracketToken::Open {
+                id: 1,
+                bracket_type: '(',
+                length: 1,
+            },
+            BracketToken::Close {
+                id: 1,
+                bracket_type: ')',
+                length: 1,
+            },
+            BracketToken::Text { length: 3 },
+        ]
+    }
+
+    fn leading_pair_marker<'a>(root: &'a AstNode<'a>) -> Option<String> {
+        match root.get_child(0)? {
+            AstNode::Pair(pair) => Some(pair.opening_bracket.metadata.clone().unwrap_or_default()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn reparse_reuses_untouched_subtree_before_edit() {
+        // The `()` pair covers 0..2, ending where the edit at 2..5 begins, so
+        // it is spliced in unchanged — its distinctive marker survives.
+        let arena = AstArena::new();
+
+        let old_root = old_tree("reused", SmallVec::new());
+
+        let mut stream = VecStream::new(tokens_for_oab());
+
+        let new_root = Parser::reparse(&old_root, 2..5, &mut stream, &arena);
+
+        assert_eq!(leading_pair_marker(&new_root).as_deref(), Some("reused"));
+    }
+
+    #[test]
+    fn reparse_rebuilds_when_can_be_reused_is_false() {
+        // Same layout, but the old pair claims an unmatched opener (id 99) that
+        // is not open in the outer context, so `can_be_reused` rejects it and
+        // the pair is rebuilt fresh — losing the marker.
+        let arena = AstArena::new();
+
+        let old_root = old_tree("reused", SmallVec::from_slice(&[99]));
+
+        let mut stream = VecStream::new(tokens_for_oab());
+
+        let new_root = Parser::reparse(&old_root, 2..5, &mut stream, &arena);
+
+        assert_eq!(leading_pair_marker(&new_root).as_deref(), Some(""));
+    }
+
+    fn text_leaf<'a>(length: u64) -> Box<AstNode<'a>> {
+        Box::new(AstNode::Text(TextAstNode {
+            length,
+            text: String::new(),
+        }))
+    }
+
+    fn assert_balanced(node: &AstNode) {
+        if let AstNode::List(list) = node {
+            assert!(
+                (2..=3).contains(&list.children.len()),
+                "interior list must hold 2 or 3 children, got {}",
+                list.children.len()
+            );
+
+            for child in &list.children {
+                assert_eq!(
+                    child.list_height(),
+                    list.list_height - 1,
+                    "children of a balanced list share one height"
+                );
+
+                assert_balanced(child);
+            }
+        }
+    }
+
+    #[test]
+    fn concat_keeps_balance_and_preserves_length() {
+        let n = 81u64;
+
+        let children: Vec<_> = (0..n).map(|_| text_leaf(1)).collect();
+
+        let root = ListAstNode::from_children_balanced(children);
+
+        // Length is the sum of the leaves, and the tree is logarithmic in `n`
+        // rather than a flat O(n)-deep list.
+        assert_eq!(root.length(), n);
+
+        assert!(
+            root.list_height() <= 2 * (n as f64).log2().ceil() as usize,
+            "height {} is not ~log(n) for n={n}",
+            root.list_height()
+        );
+
+        assert_balanced(&root);
+    }
+
+    #[test]
+    fn concat_joins_equal_height_trees_under_a_fresh_parent() {
+        let left = ListAstNode::from_children_balanced(vec![text_leaf(1), text_leaf(2)]);
+
+        let right = ListAstNode::from_children_balanced(vec![text_leaf(3), text_leaf(4)]);
+
+        let joined = concat(left, right);
+
+        assert_eq!(joined.length(), 10);
+
+        assert_eq!(joined.list_height(), 2);
+
+        assert_balanced(&joined);
+    }
+
+    #[test]
+    fn matching_bracket_round_trips_between_partners() {
+        // `(abc)`: opening at 0, three bytes of content, closing at 4.
+        let child = AstNode::Text(TextAstNode {
+            length: 3,
+            text: String::new(),
+        });
+
+        let pair = AstNode::Pair(PairAstNode {
+            length: 5,
+            opening_bracket: bracket('(', 0, None),
+            child: Some(&child),
+            closing_bracket: Some(bracket(')', 4, None)),
+            missing_opening_bracket_ids: SmallVec::new(),
+        });
+
+        assert_eq!(pair.matching_bracket(0), Some(4));
+
+        assert_eq!(pair.matching_bracket(4), Some(0));
+
+        // A position in the interior is on neither bracket.
+        assert_eq!(pair.matching_bracket(2), None);
+    }
+
+    /// [`TextModel`] that slices the region into fixed-size byte chunks so a
+    /// line's leading whitespace is split across chunk boundaries.
+    struct ChunkedModel<'a> {
+        text: &'a str,
+        chunk: usize,
+    }
+
+    impl TextModel for ChunkedModel<'_> {
+        fn text_chunks(&self, range: Range<u64>) -> impl Iterator<Item = &str> {
+            let slice = &self.text[range.start as usize..range.end as usize];
+
+            (0..slice.len())
+                .step_by(self.chunk)
+                .map(move |i| &slice[i..(i + self.chunk).min(slice.len())])
+        }
+
+        fn line_indent(&self, line: usize) -> usize {
+            self.text
+                .lines()
+                .nth(line)
+                .map(|l| l.chars().take_while(|c| c.is_whitespace()).count())
+                .unwrap_or(0)
+        }
+    }
+
+    #[test]
+    fn compute_min_indentation_streams_across_chunk_boundaries() {
+        // Two lines indented by 2 and 4 spaces; one byte per chunk forces the
+        // leading-whitespace run of every line to cross chunk boundaries.
+        let text = "  a\n    b\n";
+
+        let model = ChunkedModel { text, chunk: 1 };
+
+        let list = ListAstNode {
+            length: text.len() as u64,
+            list_height: 0,
+            missing_opening_bracket_ids: SmallVec::new(),
+            children: Vec::new(),
+        };
+
+        assert_eq!(list.compute_min_indentation(0, &model), 2);
+
+        // `line_indent` reports a single line's leading whitespace directly.
+        assert_eq!(model.line_indent(0), 2);
+
+        assert_eq!(model.line_indent(1), 4);
+    }
+}